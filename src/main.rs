@@ -18,7 +18,7 @@
 //! HTTP/1.1 200 OK
 //! content-type: application/json
 //! x-request-id: internal-87ca5d23-7d18-4485-b0c1-bff48a67a9a4
-//! content-length: 231
+//! content-length: 253
 //! date: Mon, 20 Apr 2020 14:32:29 GMT
 //!
 //! {
@@ -29,7 +29,8 @@
 //!       "data": "87ca5d23-7d18-4485-b0c1-bff48a67a9a4",
 //!       "scope": "Internal"
 //!     },
-//!     "note": "this data is injected after warp service ran",
+//!     "duration_ms": 1,
+//!     "note": "this data is injected after warp service ran"
 //!   }
 //! }
 //! ```
@@ -40,7 +41,7 @@
 //! HTTP/1.1 200 OK
 //! content-type: application/json
 //! x-request-id: my-external-request-id
-//! content-length: 217
+//! content-length: 239
 //! date: Mon, 20 Apr 2020 14:35:25 GMT
 //!
 //! {
@@ -51,19 +52,63 @@
 //!       "data": "my-external-request-id",
 //!       "scope": "External"
 //!     },
+//!     "duration_ms": 1,
+//!     "note": "this data is injected after warp service ran"
+//!   }
+//! }
+//! ```
+//!
+//! A `-H 'traceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01'` header
+//! slots the request into that distributed trace: the trace ID is kept, we mint our own
+//! span ID, and both come back as a `traceparent` response header as well as in the body:
+//!
+//! ```txt
+//! HTTP/1.1 200 OK
+//! content-type: application/json
+//! x-request-id: 4bf92f3577b34da6a3ce929d0e0e4736-b7ad6b7169203331
+//! traceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-b7ad6b7169203331-01
+//! content-length: 502
+//! date: Mon, 20 Apr 2020 14:38:10 GMT
+//!
+//! {
+//!   "op": "4 / 2",
+//!   "output": 2,
+//!   "taskLocals": {
+//!     "RequestIdInstance": {
+//!       "data": "4bf92f3577b34da6a3ce929d0e0e4736-b7ad6b7169203331",
+//!       "scope": {
+//!         "Traced": {
+//!           "flags": 1,
+//!           "span_id": "b7ad6b7169203331",
+//!           "trace_id": "4bf92f3577b34da6a3ce929d0e0e4736"
+//!         }
+//!       }
+//!     },
+//!     "duration_ms": 1,
 //!     "note": "this data is injected after warp service ran",
+//!     "span_id": "b7ad6b7169203331",
+//!     "trace_id": "4bf92f3577b34da6a3ce929d0e0e4736"
 //!   }
 //! }
 //! ```
 //!
+//! An upstream body that is too large to buffer (or too deeply nested to walk safely)
+//! does not get `taskLocals` injected; an oversized body instead gets replaced with a
+//! `500` carrying the same `ErrorMessage` shape as the rejection handler below, e.g.
+//! `{"code":500,"message":"BODY_TOO_LARGE","request_id":"..."}`.
+//!
 #![deny(warnings)]
 
 use hyper::{Body, Request, Response};
 use log::{info, warn};
+use rand::Rng;
 use serde::Serialize as SerializeTrait;
 use serde_derive::Serialize;
 use std::convert::Infallible;
 use std::num::NonZeroU16;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tower_service::Service;
 use warp::{
     http::{HeaderMap, StatusCode},
@@ -73,14 +118,77 @@ use warp::{
 // ===== custom request ID structure, note: all types must be Copy'able! =====
 
 const REQUEST_ID_PREFIX_INTERNAL: &'static str = "internal-";
-const REQUEST_ID_DATA_LENGTH: usize = 64; // usually sufficiently enough space for common request ID data
-pub type InnerRequestIdData = [u8; REQUEST_ID_DATA_LENGTH];
-pub type RequestIdData = arrayvec::ArrayString<InnerRequestIdData>;
+
+// arraystring::CacheString is a 63-byte, cache-line-sized fixed-capacity string whose
+// `from_str_truncate` walks back to the nearest UTF-8 char boundary internally, so it
+// never panics on multibyte input straddling the limit (unlike a plain `split_at`).
+// this is the single place to change to tune the max request-ID length: swap in a
+// different arraystring size (e.g. `arraystring::SmallString`) for a smaller/larger cap.
+pub type RequestIdData = arraystring::CacheString;
+
+// Crockford base32: excludes I/L/O/U to avoid visual confusion with 1/0.
+const ULID_ENCODING: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const ULID_LENGTH: usize = 26;
+
+// selected once at startup via `configure_internal_id_generator`, then read on every request
+static ULID_GENERATOR_ENABLED: AtomicBool = AtomicBool::new(false);
+// last (timestamp_ms, randomness) handed out by generate_ulid, used for best-effort monotonicity
+static LAST_ULID_STATE: Mutex<Option<(u64, u128)>> = Mutex::new(None);
+
+// reads REQUEST_ID_INTERNAL_FORMAT=ulid (default: uuidv4) once at startup
+fn configure_internal_id_generator() {
+    let use_ulid = std::env::var("REQUEST_ID_INTERNAL_FORMAT")
+        .map(|value| value.eq_ignore_ascii_case("ulid"))
+        .unwrap_or(false);
+    ULID_GENERATOR_ENABLED.store(use_ulid, Ordering::Relaxed);
+}
+
+// best-effort same-millisecond monotonicity for generate_ulid: if this draw would not sort
+// after the last one handed out, bump the last randomness by one instead. Masked to 80 bits
+// so a carry out of the randomness field can never bleed into the timestamp bits above it
+// and fabricate a later timestamp.
+fn bump_randomness_for_monotonicity(
+    timestamp_ms: u64,
+    randomness: u128,
+    last_state: Option<(u64, u128)>,
+) -> u128 {
+    match last_state {
+        Some((last_timestamp_ms, last_randomness))
+            if last_timestamp_ms == timestamp_ms && randomness <= last_randomness =>
+        {
+            last_randomness.wrapping_add(1) & ((1u128 << 80) - 1)
+        }
+        _ => randomness,
+    }
+}
+
+// encodes a 48-bit millisecond timestamp and 80 bits of randomness as a 26-char Crockford base32 ULID
+fn encode_ulid(timestamp_ms: u64, randomness: u128) -> [u8; ULID_LENGTH] {
+    let bits: u128 = ((timestamp_ms as u128) << 80) | randomness;
+    let mut output = [0u8; ULID_LENGTH];
+    for (i, slot) in output.iter_mut().enumerate() {
+        // 26 groups of 5 bits cover 130 bits; the top 2 are implicit padding since bits is 128-bit
+        let shift = 130 - 5 * (i + 1);
+        let index = ((bits >> shift) & 0x1F) as usize;
+        *slot = ULID_ENCODING[index];
+    }
+    output
+}
+
+// hex-encoded W3C trace/span ids, sized exactly to their wire length so both stay Copy
+pub type TraceIdHex = arrayvec::ArrayString<[u8; 32]>;
+pub type SpanIdHex = arrayvec::ArrayString<[u8; 16]>;
 
 #[derive(Debug, Copy, Clone, Serialize)]
 enum RequestIdScope {
     Internal,
+    InternalUlid,
     External,
+    Traced {
+        trace_id: TraceIdHex,
+        span_id: SpanIdHex,
+        flags: u8,
+    },
 }
 
 #[derive(Debug, Copy, Clone, Serialize)]
@@ -92,42 +200,144 @@ struct RequestId {
 impl RequestId {
     fn to_string(&self) -> String {
         match self.scope {
-            RequestIdScope::Internal => format!("{}{}", REQUEST_ID_PREFIX_INTERNAL, self.data),
-            RequestIdScope::External => format!("{}", self.data), // external IDs do not get tampered with (other than truncation)
+            RequestIdScope::Internal | RequestIdScope::InternalUlid => {
+                format!("{}{}", REQUEST_ID_PREFIX_INTERNAL, self.data)
+            }
+            // external and traced IDs do not get tampered with (other than truncation)
+            RequestIdScope::External | RequestIdScope::Traced { .. } => format!("{}", self.data),
+        }
+    }
+
+    // the `traceparent` value to echo back, reusing the incoming trace id and
+    // flags but our own freshly generated span id; `None` outside a traced scope
+    fn traceparent_header(&self) -> Option<String> {
+        match self.scope {
+            RequestIdScope::Traced {
+                trace_id,
+                span_id,
+                flags,
+            } => Some(format!("00-{}-{}-{:02x}", trace_id, span_id, flags)),
+            _ => None,
         }
     }
 
     fn generate_internal() -> Self {
-        let uuid_string = uuid::Uuid::new_v4().to_hyphenated_ref().to_string();
+        if ULID_GENERATOR_ENABLED.load(Ordering::Relaxed) {
+            Self::generate_ulid()
+        } else {
+            let uuid_string = uuid::Uuid::new_v4().to_hyphenated_ref().to_string();
+            Self {
+                scope: RequestIdScope::Internal,
+                data: RequestIdData::from_str_truncate(&uuid_string),
+            }
+        }
+    }
+
+    // time-sortable alternative to generate_internal's UUIDv4; selected via
+    // configure_internal_id_generator. monotonicity within the same millisecond
+    // is best-effort only: it holds for sequential calls on one process, not
+    // across concurrent generators or process restarts.
+    fn generate_ulid() -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system clock is before UNIX epoch")
+            .as_millis() as u64;
+
+        let mut randomness: u128 = {
+            let mut bytes = [0u8; 10];
+            rand::thread_rng().fill(&mut bytes);
+            let mut buf = [0u8; 16];
+            buf[6..].copy_from_slice(&bytes);
+            u128::from_be_bytes(buf)
+        };
+
+        let mut last_state = LAST_ULID_STATE.lock().unwrap();
+        randomness = bump_randomness_for_monotonicity(timestamp_ms, randomness, *last_state);
+        *last_state = Some((timestamp_ms, randomness));
+        drop(last_state);
+
+        let encoded = encode_ulid(timestamp_ms, randomness);
+        let ulid_str = std::str::from_utf8(&encoded).expect("ULID encoding is always ASCII");
         Self {
-            scope: RequestIdScope::Internal,
-            data: RequestIdData::from(&uuid_string).unwrap(),
+            scope: RequestIdScope::InternalUlid,
+            data: RequestIdData::from_str_truncate(ulid_str),
         }
     }
 
+    // `from_str_truncate` handles the char-boundary bookkeeping for us, so this never
+    // panics even when the capacity cut point would otherwise land inside a multibyte
+    // character, and never allows external data to blow up the fixed-size buffer.
     fn from_external(data: &str) -> Self {
         RequestId {
             scope: RequestIdScope::External,
-            data: RequestIdData::from(data).unwrap(),
+            data: RequestIdData::from_str_truncate(data),
         }
     }
 
-    // preferred and safe way to fill the array string, never allow external data to blow it up
-    fn from_external_truncated(unbounded: &str) -> Self {
-        // dirty way of getting the correct upper bound
-        let min_length: usize = *[unbounded.len(), REQUEST_ID_DATA_LENGTH]
-            .iter()
-            .min()
-            .unwrap(); // infallible at this point
-        let (truncated, _) = unbounded.split_at(min_length);
-        Self::from_external(truncated)
+    // W3C Trace Context: `00-<32 hex trace-id>-<16 hex parent-id>-<2 hex flags>`.
+    // we keep the caller's trace id and mint our own span id (the caller's
+    // parent-id was its own span and is not retained); malformed or all-zero
+    // ids are rejected and treated as if the header was absent entirely.
+    fn from_traceparent(value: &str) -> Option<Self> {
+        let mut fields = value.split('-');
+        let version = fields.next()?;
+        let trace_id_hex = fields.next()?;
+        let parent_id_hex = fields.next()?;
+        let flags_hex = fields.next()?;
+        if fields.next().is_some() {
+            return None; // trailing fields are a version extension we don't support
+        }
+        if version != "00" || trace_id_hex.len() != 32 || parent_id_hex.len() != 16 || flags_hex.len() != 2 {
+            return None;
+        }
+
+        let mut trace_id_bytes = [0u8; 16];
+        let mut parent_id_bytes = [0u8; 8];
+        let mut flags_bytes = [0u8; 1];
+        if !decode_hex_exact(trace_id_hex, &mut trace_id_bytes)
+            || !decode_hex_exact(parent_id_hex, &mut parent_id_bytes)
+            || !decode_hex_exact(flags_hex, &mut flags_bytes)
+        {
+            return None;
+        }
+        if trace_id_bytes == [0u8; 16] || parent_id_bytes == [0u8; 8] {
+            return None;
+        }
+
+        let span_id_bytes: [u8; 8] = {
+            let mut bytes = [0u8; 8];
+            rand::thread_rng().fill(&mut bytes);
+            bytes
+        };
+
+        let trace_id = TraceIdHex::from(&encode_hex(&trace_id_bytes)).unwrap();
+        let span_id = SpanIdHex::from(&encode_hex(&span_id_bytes)).unwrap();
+        let data = RequestIdData::from_str_truncate(format!("{}-{}", trace_id, span_id));
+
+        Some(Self {
+            scope: RequestIdScope::Traced {
+                trace_id,
+                span_id,
+                flags: flags_bytes[0],
+            },
+            data,
+        })
     }
 
-    // try to get the header value and use a truncated version, otherwise fall back to internal if missing or parsing error
+    // try traceparent first so we slot into an existing distributed trace, then the
+    // header value and a truncated version, otherwise fall back to internal if missing or parsing error
     fn from_headers_or_internal(headers: &HeaderMap) -> Self {
+        if let Some(traced) = headers
+            .get("traceparent")
+            .and_then(|hvalue| hvalue.to_str().ok())
+            .and_then(RequestId::from_traceparent)
+        {
+            return traced;
+        }
+
         match headers.get("x-request-id") {
             Some(hvalue) => match hvalue.to_str() {
-                Ok(valid) => RequestId::from_external_truncated(valid),
+                Ok(valid) => RequestId::from_external(valid),
                 Err(_) => RequestId::generate_internal(),
             },
             None => RequestId::generate_internal(),
@@ -135,10 +345,70 @@ impl RequestId {
     }
 }
 
+// decodes exactly `out.len()` bytes of lowercase/uppercase hex from `hex`, rejecting any other length or non-hex input
+fn decode_hex_exact(hex: &str, out: &mut [u8]) -> bool {
+    if hex.len() != out.len() * 2 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return false;
+    }
+    for (i, slot) in out.iter_mut().enumerate() {
+        match u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16) {
+            Ok(byte) => *slot = byte,
+            Err(_) => return false,
+        }
+    }
+    true
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
 // the needed magic!
 // could not find a better way to think about how to deal with data needed for the full request-response cycle
 tokio::task_local! {
-    static REQ_ID: RequestId;
+    static REQ_CTX: RequestContext;
+}
+
+// carries the request ID alongside a start timestamp so handlers downstream of
+// make_svc can report how long the whole request-response cycle took
+#[derive(Debug, Copy, Clone)]
+struct RequestContext {
+    request_id: RequestId,
+    started_at: Instant,
+}
+
+// default threshold for the slow-request warning below, tunable via configure_slow_request_threshold
+const DEFAULT_SLOW_REQUEST_THRESHOLD_MS: u64 = 1000;
+static SLOW_REQUEST_THRESHOLD_MS: AtomicU64 = AtomicU64::new(DEFAULT_SLOW_REQUEST_THRESHOLD_MS);
+
+// reads SLOW_REQUEST_THRESHOLD_MS (milliseconds) once at startup
+fn configure_slow_request_threshold() {
+    let threshold_ms = std::env::var("SLOW_REQUEST_THRESHOLD_MS")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_SLOW_REQUEST_THRESHOLD_MS);
+    SLOW_REQUEST_THRESHOLD_MS.store(threshold_ms, Ordering::Relaxed);
+}
+
+// guards `modify_body`'s buffering and JSON walk against hostile upstream bodies, see below
+const DEFAULT_MAX_BODY_BYTES: u64 = 1_000_000; // 1 MB
+const DEFAULT_MAX_JSON_NESTING_DEPTH: u64 = 32;
+static MAX_BODY_BYTES: AtomicU64 = AtomicU64::new(DEFAULT_MAX_BODY_BYTES);
+static MAX_JSON_NESTING_DEPTH: AtomicU64 = AtomicU64::new(DEFAULT_MAX_JSON_NESTING_DEPTH);
+
+// reads MAX_BODY_BYTES and MAX_JSON_NESTING_DEPTH once at startup
+fn configure_body_limits() {
+    let max_body_bytes = std::env::var("MAX_BODY_BYTES")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES);
+    MAX_BODY_BYTES.store(max_body_bytes, Ordering::Relaxed);
+
+    let max_json_nesting_depth = std::env::var("MAX_JSON_NESTING_DEPTH")
+        .ok()
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MAX_JSON_NESTING_DEPTH);
+    MAX_JSON_NESTING_DEPTH.store(max_json_nesting_depth, Ordering::Relaxed);
 }
 
 // ===== MAIN =====
@@ -147,6 +417,9 @@ tokio::task_local! {
 #[tokio::main]
 async fn main() -> Result<(), hyper::error::Error> {
     pretty_env_logger::init();
+    configure_internal_id_generator();
+    configure_slow_request_threshold();
+    configure_body_limits();
 
     let math = warp::path!("math" / u16)
         .and(div_by())
@@ -162,20 +435,43 @@ async fn main() -> Result<(), hyper::error::Error> {
         .and(math)
         .recover(handle_rejection)
         // we can access the task local and attach the header to our response with warp land:
-        .map(|reply| warp::reply::with_header(reply, "x-request-id", REQ_ID.get().to_string()))
+        .map(|reply| {
+            let request_id = REQ_CTX.get().request_id;
+            let reply = warp::reply::with_header(reply, "x-request-id", request_id.to_string());
+            match request_id.traceparent_header() {
+                Some(traceparent) => {
+                    warp::reply::with_header(reply, "traceparent", traceparent).into_response()
+                }
+                None => reply.into_response(),
+            }
+        })
         .with(warp::log("app"));
 
     let mut warp_svc = warp::service(routes);
     let make_svc = hyper::service::make_service_fn(move |_| async move {
         let svc = hyper::service::service_fn(move |req: Request<Body>| async move {
             let request_id = RequestId::from_headers_or_internal(req.headers());
-            REQ_ID
-                .scope(request_id, async move {
-                    info!("current request ID: {:?}", REQ_ID.get());
+            let request_context = RequestContext {
+                request_id,
+                started_at: Instant::now(),
+            };
+            REQ_CTX
+                .scope(request_context, async move {
+                    info!("current request ID: {:?}", REQ_CTX.get().request_id);
                     let warp_svc_response = warp_svc.call(req).await;
-                    let (parts, body) = warp_svc_response.unwrap().into_parts();
-                    // after example: attach request ID to body
-                    let body = modify_body(body).await;
+                    let (mut parts, body) = warp_svc_response.unwrap().into_parts();
+                    let elapsed = REQ_CTX.get().started_at.elapsed();
+                    let duration_ms = elapsed.as_millis() as u64;
+                    if duration_ms > SLOW_REQUEST_THRESHOLD_MS.load(Ordering::Relaxed) {
+                        warn!(
+                            "slow request {:?}: took {}ms",
+                            REQ_CTX.get().request_id,
+                            duration_ms
+                        );
+                    }
+                    // after example: attach request ID and timing to body
+                    let (status, body) = modify_body(body, duration_ms, parts.status).await;
+                    parts.status = status;
                     let rebuilt = Response::from_parts(parts, body);
                     Ok::<Response<Body>, Infallible>(rebuilt)
                 })
@@ -192,39 +488,134 @@ async fn main() -> Result<(), hyper::error::Error> {
 
 // same type out as in; you could add more arguments to use for body transformations
 // like passing in a request ID which gets attached to a JSON property
-async fn modify_body(body: hyper::body::Body) -> hyper::body::Body {
-    let body_string = body_to_string(body).await;
-    let mut json_value: serde_json::Value =
-        serde_json::from_str(&body_string).expect("body must be valid JSON");
-
-    // attach our task local data
-    let json_object = json_value.as_object_mut().expect("value must be an object");
-    json_object.insert(
-        "taskLocals".into(),
-        serde_json::json!({
-            "note": "this data is injected after warp service ran",
-            "RequestIdInstance": REQ_ID.get()
-        }),
-    );
+//
+// bounded against hostile upstream bodies: oversized or unreadable bodies abort
+// with a clean 500 (status is returned so the caller can override the original
+// one), anything that isn't a UTF-8 JSON object is passed through untouched
+// rather than panicking, and pathologically nested JSON skips injection instead
+// of risking a stack overflow while we walk it
+async fn modify_body(
+    body: hyper::body::Body,
+    duration_ms: u64,
+    status: StatusCode,
+) -> (StatusCode, hyper::body::Body) {
+    let max_body_bytes = MAX_BODY_BYTES.load(Ordering::Relaxed) as usize;
+    let bytes = match collect_body_bounded(body, max_body_bytes).await {
+        Ok(bytes) => bytes,
+        Err(BodyCollectError::TooLarge) => {
+            warn!("response body exceeded {} bytes, aborting", max_body_bytes);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "BODY_TOO_LARGE");
+        }
+        Err(BodyCollectError::Read(err)) => {
+            warn!("failed to read response body: {:?}", err);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "BODY_READ_ERROR");
+        }
+    };
+
+    let body_string = match String::from_utf8(bytes) {
+        Ok(body_string) => body_string,
+        Err(err) => {
+            warn!("response body is not valid UTF-8, passing it through untouched");
+            return (status, Body::from(err.into_bytes()));
+        }
+    };
+
+    let mut json_value: serde_json::Value = match serde_json::from_str(&body_string) {
+        Ok(json_value) => json_value,
+        Err(err) => {
+            warn!(
+                "response body is not valid JSON ({}), passing it through untouched",
+                err
+            );
+            return (status, Body::from(body_string));
+        }
+    };
+
+    let max_depth = MAX_JSON_NESTING_DEPTH.load(Ordering::Relaxed) as usize;
+    if json_nesting_exceeds(&json_value, max_depth) {
+        warn!(
+            "response body JSON nests deeper than {} levels, skipping taskLocals injection",
+            max_depth
+        );
+        return (status, Body::from(body_string));
+    }
+
+    let json_object = match json_value.as_object_mut() {
+        Some(json_object) => json_object,
+        None => {
+            warn!("response body is not a JSON object, passing it through untouched");
+            return (status, Body::from(body_string));
+        }
+    };
+    let request_id = REQ_CTX.get().request_id;
+    let mut task_locals = serde_json::json!({
+        "note": "this data is injected after warp service ran",
+        "RequestIdInstance": request_id,
+        "duration_ms": duration_ms
+    });
+    if let RequestIdScope::Traced { trace_id, span_id, .. } = request_id.scope {
+        task_locals["trace_id"] = serde_json::Value::String(trace_id.to_string());
+        task_locals["span_id"] = serde_json::Value::String(span_id.to_string());
+    }
+    json_object.insert("taskLocals".into(), task_locals);
 
     let final_body = print_json(&json_object);
-    Body::from(final_body)
+    (status, Body::from(final_body))
+}
+
+// builds the same shape handle_rejection uses, so a body that had to be rejected
+// looks like any other error response to callers
+fn error_response(code: StatusCode, message: &str) -> (StatusCode, hyper::body::Body) {
+    let json = print_json(&ErrorMessage {
+        code: code.as_u16(),
+        message: message.into(),
+        request_id: REQ_CTX.get().request_id.to_string(),
+    });
+    (code, hyper::body::Body::from(json))
+}
+
+#[derive(Debug)]
+enum BodyCollectError {
+    TooLarge,
+    Read(hyper::Error),
 }
 
 // this is mostly copy-pasta from the internet since I have zero idea how to easily collect the data;
 // why do I have to make such a mess in the first place? a dbg!() showed it was just a
 // single `Body { Full { ... } }` (so also on a single chunk containing all the data);
 // I hope this really gets optimized away ...
-async fn body_to_string(body: hyper::body::Body) -> String {
+//
+// accumulates chunks up to `max_bytes` and bails out instead of buffering forever
+async fn collect_body_bounded(
+    mut body: hyper::body::Body,
+    max_bytes: usize,
+) -> Result<Vec<u8>, BodyCollectError> {
     use futures::TryStreamExt;
-    let entire_body = body
-        .try_fold(Vec::new(), |mut data, chunk| async move {
-            data.extend_from_slice(&chunk);
-            Ok(data)
-        })
-        .await
-        .expect("body must be collectible into a Vec<u8>");
-    String::from_utf8(entire_body).expect("body must be a valid UTF8 string")
+    let mut buffer = Vec::new();
+    while let Some(chunk) = body.try_next().await.map_err(BodyCollectError::Read)? {
+        buffer.extend_from_slice(&chunk);
+        if buffer.len() > max_bytes {
+            return Err(BodyCollectError::TooLarge);
+        }
+    }
+    Ok(buffer)
+}
+
+// bounds its own recursion to `max_depth` so a pathologically nested payload can't
+// blow the stack during the depth check itself, not just during the later walk
+fn json_nesting_exceeds(value: &serde_json::Value, max_depth: usize) -> bool {
+    fn walk(value: &serde_json::Value, remaining_depth: usize) -> bool {
+        match value {
+            serde_json::Value::Array(items) => {
+                remaining_depth == 0 || items.iter().any(|item| walk(item, remaining_depth - 1))
+            }
+            serde_json::Value::Object(map) => {
+                remaining_depth == 0 || map.values().any(|item| walk(item, remaining_depth - 1))
+            }
+            _ => false,
+        }
+    }
+    walk(value, max_depth)
 }
 
 // pretty and with final newline
@@ -290,8 +681,249 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     let json = warp::reply::json(&ErrorMessage {
         code: code.as_u16(),
         message: message.into(),
-        request_id: REQ_ID.get().to_string(), // added!
+        request_id: REQ_CTX.get().request_id.to_string(), // added!
     });
     let reply_with_status = warp::reply::with_status(json, code);
     Ok(reply_with_status)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // same vectors as the `truncate_emoji_str`/`truncate_str` benches in benches/fixedstrings.rs,
+    // which compare this exact truncation approach against a plain ArrayString + split_at
+    const TEST_STR: &str = "This is a very long string and should get truncated at some point, because we have a fixed length.";
+    const TEST_EXPECTED: &str = "This is a very long string and should get truncated at some poi";
+
+    const EMOJI_STR: &str =
+        "Let's run test strings with some special chars like emojis 👨‍👨‍👦‍👦.";
+    const EMOJI_EXPECTED: &str = "Let's run test strings with some special chars like emojis 👨";
+
+    #[test]
+    fn truncates_plain_ascii_to_capacity() {
+        let request_id = RequestId::from_external(TEST_STR);
+        assert_eq!(TEST_EXPECTED, request_id.data.as_str());
+    }
+
+    #[test]
+    fn truncates_multibyte_input_at_a_char_boundary() {
+        let request_id = RequestId::from_external(EMOJI_STR);
+        assert_eq!(EMOJI_EXPECTED, request_id.data.as_str());
+    }
+
+    #[test]
+    fn never_panics_regardless_of_where_the_cut_lands_inside_a_multibyte_char() {
+        let emoji = "👨"; // 4 bytes, so this sweep lands the 63-byte cut at every offset inside it
+        for base_len in 58..=66 {
+            let input = format!("{}{}", "x".repeat(base_len), emoji);
+            let request_id = RequestId::from_external(&input);
+            assert!(request_id.data.as_str().len() <= 63);
+            assert!(input.starts_with(request_id.data.as_str()));
+        }
+    }
+
+    fn test_request_context() -> RequestContext {
+        RequestContext {
+            request_id: RequestId::generate_internal(),
+            started_at: Instant::now(),
+        }
+    }
+
+    // builds `{"payload": [[[...null...]]]}` with `depth` levels of array nesting
+    fn nested_json_string(depth: usize) -> String {
+        let mut value = String::from("null");
+        for _ in 0..depth {
+            value = format!("[{}]", value);
+        }
+        format!("{{\"payload\":{}}}", value)
+    }
+
+    #[tokio::test]
+    async fn modify_body_rejects_oversized_bodies_with_a_clean_500() {
+        REQ_CTX
+            .scope(test_request_context(), async {
+                let oversized = vec![b'a'; DEFAULT_MAX_BODY_BYTES as usize + 1];
+                let (status, body) =
+                    modify_body(Body::from(oversized), 0, StatusCode::OK).await;
+                assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+
+                let bytes = hyper::body::to_bytes(body).await.unwrap();
+                let json: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+                assert_eq!(json["message"], "BODY_TOO_LARGE");
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn modify_body_skips_injection_for_deeply_nested_json() {
+        REQ_CTX
+            .scope(test_request_context(), async {
+                let deeply_nested = nested_json_string(DEFAULT_MAX_JSON_NESTING_DEPTH as usize + 8);
+                let (status, body) =
+                    modify_body(Body::from(deeply_nested.clone()), 0, StatusCode::OK).await;
+                assert_eq!(status, StatusCode::OK);
+
+                let bytes = hyper::body::to_bytes(body).await.unwrap();
+                assert_eq!(bytes.as_ref(), deeply_nested.as_bytes());
+            })
+            .await;
+    }
+
+    #[test]
+    fn encode_ulid_is_lexicographically_increasing_with_timestamp() {
+        let earlier = encode_ulid(1_000, 0);
+        let later = encode_ulid(1_001, 0);
+        assert!(earlier < later);
+    }
+
+    #[test]
+    fn encode_ulid_is_lexicographically_increasing_with_randomness_at_same_timestamp() {
+        let lower = encode_ulid(1_000, 42);
+        let higher = encode_ulid(1_000, 43);
+        assert!(lower < higher);
+    }
+
+    #[test]
+    fn bump_randomness_for_monotonicity_leaves_distinct_timestamps_untouched() {
+        let bumped = bump_randomness_for_monotonicity(2_000, 5, Some((1_000, 5)));
+        assert_eq!(bumped, 5);
+    }
+
+    #[test]
+    fn bump_randomness_for_monotonicity_leaves_already_increasing_draws_untouched() {
+        let bumped = bump_randomness_for_monotonicity(1_000, 50, Some((1_000, 5)));
+        assert_eq!(bumped, 50);
+    }
+
+    #[test]
+    fn bump_randomness_for_monotonicity_increments_on_a_same_millisecond_regression() {
+        let bumped = bump_randomness_for_monotonicity(1_000, 3, Some((1_000, 5)));
+        assert_eq!(bumped, 6);
+    }
+
+    #[test]
+    fn bump_randomness_for_monotonicity_masks_the_carry_out_of_the_80_bit_field() {
+        // reproduces the regression fixed in a09e228: seeding right at the top of the
+        // 80-bit randomness field must never let the `+1` bump carry into the
+        // timestamp bits stored directly above it in the combined ULID bit layout
+        let max_randomness = (1u128 << 80) - 1;
+        let bumped =
+            bump_randomness_for_monotonicity(1_000, max_randomness, Some((1_000, max_randomness)));
+
+        assert!(bumped < (1u128 << 80), "carry bled past the 80-bit field");
+        assert_eq!(bumped, 0);
+
+        let timestamp_ms = 1_000u64;
+        let combined = ((timestamp_ms as u128) << 80) | bumped;
+        assert_eq!(
+            combined >> 80,
+            timestamp_ms as u128,
+            "timestamp bits were corrupted by the randomness carry"
+        );
+    }
+
+    fn seed_last_ulid_state(timestamp_ms: u64, randomness: u128) {
+        *LAST_ULID_STATE.lock().unwrap() = Some((timestamp_ms, randomness));
+    }
+
+    #[test]
+    fn generate_ulid_does_not_regress_into_timestamp_bits_when_seeded_near_overflow() {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        seed_last_ulid_state(timestamp_ms, (1u128 << 80) - 1);
+
+        let request_id = RequestId::generate_ulid();
+        let ulid_str = request_id.data.as_str();
+
+        // decode the 26-char Crockford base32 ULID back into its 128 bits so we can
+        // check the timestamp region directly, independent of char-boundary alignment
+        let mut bits: u128 = 0;
+        for ch in ulid_str.chars() {
+            let value = ULID_ENCODING
+                .iter()
+                .position(|&b| b == ch as u8)
+                .expect("generated ULID must only contain Crockford base32 characters") as u128;
+            bits = (bits << 5) | value;
+        }
+        let decoded_timestamp_ms = (bits >> 80) as u64;
+
+        // if this call landed in the same millisecond we seeded, the carry must not
+        // have bled upward; if the clock ticked over, a normal +1ms advance is fine
+        assert!(
+            decoded_timestamp_ms == timestamp_ms || decoded_timestamp_ms == timestamp_ms + 1,
+            "ULID timestamp jumped unexpectedly: seeded {}, decoded {}",
+            timestamp_ms,
+            decoded_timestamp_ms
+        );
+    }
+
+    fn valid_traceparent() -> &'static str {
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    }
+
+    #[test]
+    fn from_traceparent_accepts_a_valid_header() {
+        let request_id =
+            RequestId::from_traceparent(valid_traceparent()).expect("valid traceparent rejected");
+        match request_id.scope {
+            RequestIdScope::Traced {
+                trace_id, flags, ..
+            } => {
+                assert_eq!(trace_id.as_str(), "4bf92f3577b34da6a3ce929d0e0e4736");
+                assert_eq!(flags, 0x01);
+            }
+            other => panic!("expected Traced scope, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn from_traceparent_rejects_all_zero_trace_id() {
+        let header = "00-00000000000000000000000000000000-00f067aa0ba902b7-01";
+        assert!(RequestId::from_traceparent(header).is_none());
+    }
+
+    #[test]
+    fn from_traceparent_rejects_all_zero_parent_id() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01";
+        assert!(RequestId::from_traceparent(header).is_none());
+    }
+
+    #[test]
+    fn from_traceparent_rejects_wrong_length_trace_id() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01";
+        assert!(RequestId::from_traceparent(header).is_none());
+    }
+
+    #[test]
+    fn from_traceparent_rejects_wrong_length_parent_id() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902-01";
+        assert!(RequestId::from_traceparent(header).is_none());
+    }
+
+    #[test]
+    fn from_traceparent_rejects_wrong_length_flags() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1";
+        assert!(RequestId::from_traceparent(header).is_none());
+    }
+
+    #[test]
+    fn from_traceparent_rejects_non_hex_characters() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e473g-00f067aa0ba902b7-01";
+        assert!(RequestId::from_traceparent(header).is_none());
+    }
+
+    #[test]
+    fn from_traceparent_rejects_unsupported_version() {
+        let header = "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        assert!(RequestId::from_traceparent(header).is_none());
+    }
+
+    #[test]
+    fn from_traceparent_rejects_trailing_extra_fields() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra";
+        assert!(RequestId::from_traceparent(header).is_none());
+    }
+}